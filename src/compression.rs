@@ -0,0 +1,198 @@
+//! Transparent LZMA decompression for Source 2 VPK entries.
+//!
+//! Source 2 VPKs may store an entry's archive bytes LZMA-compressed, with
+//! [`crate::entry::VPKDirectoryEntry::file_length`] holding the on-disk
+//! compressed size rather than the real size. [`VPKDecompressingReader`]
+//! decodes the stream as bytes are pulled through `poll_read`, so large
+//! compressed assets never force a full in-memory inflate.
+//!
+//! Gated behind the `lzma` feature; plain (uncompressed) VPK users pull in
+//! neither `lzma-rs` nor the decoding thread this spawns.
+
+use crate::entry::VALVE_LZMA_MAGIC;
+use async_channel::Receiver;
+use futures_lite::{ready, AsyncRead};
+use std::future::Future;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Valve's LZMA block header, as it precedes an entry's compressed bytes
+/// in the archive: a magic, the real (decompressed) and on-disk
+/// (compressed) sizes as separate little-endian `u32`s, and the same
+/// 5-byte LZMA properties (lc/lp/pb packed byte + little-endian dict
+/// size) the standalone `.lzma` container carries — just laid out
+/// differently, and without the container's own 8-byte uncompressed-size
+/// field.
+struct ValveLzmaHeader {
+    actual_size: u32,
+    properties: [u8; 5],
+}
+
+impl ValveLzmaHeader {
+    /// Encoded size of this header: 4-byte magic + 4-byte actual size +
+    /// 4-byte compressed size + 5-byte properties.
+    const ENCODED_LEN: u64 = 4 + 4 + 4 + 5;
+
+    fn read(source: &mut impl Read) -> std::io::Result<Self> {
+        let mut magic = [0u8; 4];
+        source.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) != VALVE_LZMA_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "missing Valve LZMA block magic",
+            ));
+        }
+
+        let mut actual_size = [0u8; 4];
+        source.read_exact(&mut actual_size)?;
+        // The on-disk compressed size is already known to the caller as
+        // `compressed_len` (the entry's `file_length` minus this header),
+        // so it is read past here only to keep the cursor aligned.
+        let mut lzma_size = [0u8; 4];
+        source.read_exact(&mut lzma_size)?;
+        let mut properties = [0u8; 5];
+        source.read_exact(&mut properties)?;
+
+        Ok(Self {
+            actual_size: u32::from_le_bytes(actual_size),
+            properties,
+        })
+    }
+
+    /// The equivalent standalone `.lzma` container header: the same 5
+    /// properties bytes, followed by the uncompressed size as a
+    /// little-endian `u64`. Reassembling it lets the raw LZMA stream that
+    /// follows the Valve header decode through the existing
+    /// [`lzma_rs::lzma_decompress`] instead of needing a headerless decode
+    /// path.
+    fn container_header(&self) -> [u8; 13] {
+        let mut header = [0u8; 13];
+        header[..5].copy_from_slice(&self.properties);
+        header[5..].copy_from_slice(&(self.actual_size as u64).to_le_bytes());
+        header
+    }
+}
+
+/// Number of decoded chunks buffered between the decompression thread and
+/// the [`VPKDecompressingReader`] consuming them.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Sink that forwards each write lzma-rs makes into the decode channel, so
+/// decompressed bytes reach the reader as soon as they are produced
+/// instead of only once decoding finishes.
+struct ChannelWriter {
+    tx: async_channel::Sender<std::io::Result<Vec<u8>>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx
+            .send_blocking(Ok(buf.to_vec()))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "decompression receiver dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A reader that transparently LZMA-decompresses the archive bytes of a
+/// compressed [`crate::entry::VPKEntry`] as they are pulled through it.
+pub struct VPKDecompressingReader {
+    // Boxed so this struct stays `Unpin` regardless of `Receiver`'s own
+    // pinning requirements; `poll_read` never needs to pin-project through it.
+    chunks: Box<Receiver<std::io::Result<Vec<u8>>>>,
+    current: std::io::Cursor<Vec<u8>>,
+    #[allow(clippy::type_complexity)]
+    pending: Option<Pin<Box<dyn Future<Output = Result<std::io::Result<Vec<u8>>, async_channel::RecvError>> + Send>>>,
+    done: bool,
+}
+
+impl VPKDecompressingReader {
+    /// Spawn a decompression thread reading a [`ValveLzmaHeader`] followed
+    /// by its LZMA-compressed bytes, together `compressed_len` bytes
+    /// starting at `offset` in `archive_path`, and return a reader that
+    /// yields the decoded bytes as they arrive.
+    pub(crate) fn spawn(archive_path: PathBuf, offset: u64, compressed_len: u64) -> Self {
+        let (tx, rx) = async_channel::bounded(CHANNEL_CAPACITY);
+
+        blocking::unblock(move || {
+            let result = (|| -> std::io::Result<()> {
+                let mut file = std::fs::File::open(&archive_path)?;
+                std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(offset))?;
+                let mut file = std::io::BufReader::new(file);
+
+                let header = ValveLzmaHeader::read(&mut file)?;
+                let lzma_len = compressed_len
+                    .checked_sub(ValveLzmaHeader::ENCODED_LEN)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "entry shorter than its LZMA header"))?;
+
+                // lzma-rs decodes the standalone `.lzma` container format, not
+                // Valve's header, so prepend the equivalent container header
+                // to the raw stream rather than teaching it a new format.
+                let mut source = std::io::Cursor::new(header.container_header()).chain(file.take(lzma_len));
+                let mut sink = ChannelWriter { tx: tx.clone() };
+                lzma_rs::lzma_decompress(&mut source, &mut sink)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+            })();
+
+            if let Err(err) = result {
+                let _ = tx.send_blocking(Err(err));
+            }
+        })
+        .detach();
+
+        Self {
+            chunks: Box::new(rx),
+            current: std::io::Cursor::new(Vec::new()),
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+impl AsyncRead for VPKDecompressingReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            let n = Read::read(&mut this.current, buf)?;
+            if n > 0 {
+                return Poll::Ready(Ok(n));
+            }
+
+            if this.done {
+                return Poll::Ready(Ok(0));
+            }
+
+            if this.pending.is_none() {
+                let rx = this.chunks.clone();
+                this.pending = Some(Box::pin(async move { rx.recv().await }));
+            }
+
+            match ready!(this.pending.as_mut().unwrap().as_mut().poll(cx)) {
+                Ok(Ok(chunk)) => {
+                    this.pending = None;
+                    this.current = std::io::Cursor::new(chunk);
+                }
+                Ok(Err(err)) => {
+                    this.pending = None;
+                    this.done = true;
+                    return Poll::Ready(Err(err));
+                }
+                Err(_) => {
+                    this.pending = None;
+                    this.done = true;
+                    return Poll::Ready(Ok(0));
+                }
+            }
+        }
+    }
+}
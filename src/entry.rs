@@ -1,13 +1,26 @@
+#[cfg(feature = "lzma")]
+use crate::compression::VPKDecompressingReader;
+use crate::read_at::{ArchiveHandles, VPKPositionedReader};
 use async_fs::File;
-use binrw::BinRead;
-use futures_lite::io::{SeekFrom, Take};
+use binrw::{BinRead, BinWrite};
+use crc32fast::Hasher;
+use futures_lite::io::{BufReader, SeekFrom, Take};
 use futures_lite::{ready, AsyncRead, AsyncReadExt, AsyncSeekExt, FutureExt};
 use std::borrow::Cow;
-use std::io::{Error, Read};
+use std::io::{Error, ErrorKind, Read};
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::Context;
 use std::task::Poll;
 
+/// Magic ("LZMA" as little-endian bytes) opening Valve's own LZMA block
+/// framing, used to mark an entry's archive bytes as compressed. The VPK
+/// directory entry format this crate parses has no compression flag, so
+/// this is read directly off the archive bytes rather than out of
+/// [`VPKDirectoryEntry`].
+pub(crate) const VALVE_LZMA_MAGIC: u32 = 0x414D_5A4C;
+
 /// An entry in the VPK.
 #[derive(Debug)]
 pub struct VPKEntry {
@@ -28,7 +41,7 @@ pub struct VPKEntry {
 
 impl VPKEntry {
     /// Get the data of the [`VPKEntry`].
-    pub async fn get(&self) -> Result<Cow<[u8]>, Error> {
+    pub async fn get(&self) -> Result<Cow<'_, [u8]>, Error> {
         let mut reader = self.reader().await?;
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf).await?;
@@ -37,19 +50,231 @@ impl VPKEntry {
 
     /// Create a [`VPKEntryReader`].
     pub async fn reader(&self) -> Result<VPKEntryReader<'_>, Error> {
+        self.reader_with_capacity(DEFAULT_BUFFER_CAPACITY).await
+    }
+
+    /// Like [`Self::reader`], but buffering archive-file reads `capacity`
+    /// bytes at a time instead of [`DEFAULT_BUFFER_CAPACITY`].
+    pub async fn reader_with_capacity(&self, capacity: usize) -> Result<VPKEntryReader<'_>, Error> {
         let Some(path) = self.archive_path.as_ref() else {
-            return Ok(VPKEntryReader::new(&self.preload_data, None));
+            return Ok(VPKEntryReader::with_capacity(&self.preload_data, None, capacity));
         };
 
         let mut file = File::open(path.as_path()).await?;
         file.seek(SeekFrom::Start(self.dir_entry.archive_offset as u64))
             .await?;
+
+        // Peeking for Valve's LZMA magic only makes sense when the `lzma`
+        // feature can actually decode what it finds; gating it out here
+        // means plain VPK users pay neither this read nor a second
+        // open+seek on top of the one above.
+        #[cfg(feature = "lzma")]
+        if self.dir_entry.file_length >= 4 {
+            let mut magic = [0u8; 4];
+            file.read_exact(&mut magic).await?;
+            if u32::from_le_bytes(magic) == VALVE_LZMA_MAGIC {
+                let reader = VPKDecompressingReader::spawn(
+                    path.as_path().to_path_buf(),
+                    self.dir_entry.archive_offset as u64,
+                    self.dir_entry.file_length as u64,
+                );
+                return Ok(VPKEntryReader::new_compressed(&self.preload_data, reader));
+            }
+            // Not compressed: rewind the 4 peeked bytes rather than pay for
+            // a second open to get a fresh cursor.
+            file.seek(SeekFrom::Start(self.dir_entry.archive_offset as u64))
+                .await?;
+        }
+
         let file = file.take(self.dir_entry.file_length as u64);
 
-        Ok(VPKEntryReader::new(&self.preload_data, Some(file)))
+        Ok(VPKEntryReader::with_capacity(
+            &self.preload_data,
+            Some(file),
+            capacity,
+        ))
+    }
+
+    /// Whether this entry's archive bytes are LZMA-compressed, as Source 2
+    /// VPKs may store them.
+    ///
+    /// The on-disk [`VPKDirectoryEntry`] carries no compression flag, so
+    /// this is determined by peeking the first 4 bytes at
+    /// [`VPKDirectoryEntry::archive_offset`] for [`VALVE_LZMA_MAGIC`].
+    /// Always `false` when [`Self::archive_path`] is `None`, since
+    /// preloaded-only entries have nothing to peek.
+    ///
+    /// Only available with the `lzma` feature: without it, nothing in the
+    /// crate can do anything with the answer, so plain VPK users pay
+    /// neither this open nor its read.
+    #[cfg(feature = "lzma")]
+    pub async fn is_compressed(&self) -> Result<bool, Error> {
+        let Some(path) = self.archive_path.as_ref() else {
+            return Ok(false);
+        };
+        if self.dir_entry.file_length < 4 {
+            return Ok(false);
+        }
+
+        let mut file = File::open(path.as_path()).await?;
+        file.seek(SeekFrom::Start(self.dir_entry.archive_offset as u64))
+            .await?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic).await?;
+        Ok(u32::from_le_bytes(magic) == VALVE_LZMA_MAGIC)
+    }
+
+    /// Create a [`VPKVerifyingReader`] that checks the entry's data against
+    /// [`VPKDirectoryEntry::crc32`] as it is read.
+    ///
+    /// The checksum is accumulated incrementally from every byte yielded by
+    /// the wrapped [`VPKEntryReader`] (preload and archive bytes alike), so
+    /// verification adds no buffering or extra copies. The check is only
+    /// performed once the reader is read to EOF; a caller that reads a
+    /// prefix and then drops the reader will not see a mismatch reported.
+    pub async fn verified_reader(&self) -> Result<VPKVerifyingReader<'_>, Error> {
+        let reader = self.reader().await?;
+        Ok(VPKVerifyingReader::new(reader, self.dir_entry.crc32))
+    }
+
+    /// Create a [`VPKPositionedReader`] that reads the archive portion of
+    /// this entry via a positioned read against a shared handle from
+    /// `handles`, instead of opening and seeking a fresh [`File`].
+    ///
+    /// Every [`VPKEntry`] whose [`Self::archive_path`] points at the same
+    /// physical file reuses the handle `handles` caches for it, so many
+    /// entries can be read concurrently off one open file descriptor —
+    /// the access pattern a parallel extractor needs.
+    pub fn reader_at(&self, handles: &ArchiveHandles) -> Result<VPKPositionedReader, Error> {
+        let Some(path) = self.archive_path.as_ref() else {
+            return Ok(VPKPositionedReader::preloaded(self.preload_data.clone()));
+        };
+
+        let archive = handles.open(path.as_path())?;
+
+        Ok(VPKPositionedReader::new(
+            self.preload_data.clone(),
+            archive,
+            self.dir_entry.archive_offset as u64,
+            self.dir_entry.file_length as u64,
+        ))
     }
+
+    /// The on-disk (possibly compressed) and real size of this entry's
+    /// data, including the preloaded portion.
+    ///
+    /// With the `lzma` feature, a compressed entry's real size is read
+    /// straight off Valve's LZMA header rather than
+    /// [`VPKDirectoryEntry::uncompressed_length`], which nothing in this
+    /// crate's directory parser populates.
+    pub async fn sizes(&self) -> Result<EntrySizes, Error> {
+        let preload_len = self.preload_data.len() as u64;
+        let compressed = preload_len + self.dir_entry.file_length as u64;
+        let fallback_uncompressed = preload_len
+            + self
+                .dir_entry
+                .uncompressed_length
+                .unwrap_or(self.dir_entry.file_length) as u64;
+
+        #[cfg(feature = "lzma")]
+        if let Some(path) = self.archive_path.as_ref() {
+            if self.dir_entry.file_length >= 8 {
+                let mut file = File::open(path.as_path()).await?;
+                file.seek(SeekFrom::Start(self.dir_entry.archive_offset as u64))
+                    .await?;
+                let mut header = [0u8; 8];
+                file.read_exact(&mut header).await?;
+                if u32::from_le_bytes(header[..4].try_into().unwrap()) == VALVE_LZMA_MAGIC {
+                    let actual_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+                    return Ok(EntrySizes {
+                        compressed,
+                        uncompressed: preload_len + actual_size as u64,
+                    });
+                }
+            }
+        }
+
+        Ok(EntrySizes {
+            compressed,
+            uncompressed: fallback_uncompressed,
+        })
+    }
+}
+
+/// The on-disk and real size of a [`VPKEntry`]'s data.
+///
+/// The two differ only for LZMA-compressed Source 2 entries; for plain
+/// entries `compressed == uncompressed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntrySizes {
+    /// Size of the entry's data as stored on disk.
+    pub compressed: u64,
+    /// Real size of the entry's data once decompressed.
+    pub uncompressed: u64,
+}
+
+/// A [`VPKEntryReader`] wrapper that verifies the entry's CRC32 checksum as
+/// data is streamed through it.
+///
+/// Every byte returned from the inner reader is fed into an incremental
+/// CRC32 (IEEE/zlib polynomial) accumulator. Once the inner reader reaches
+/// EOF, the finalized checksum is compared against the expected value; a
+/// mismatch is surfaced as an [`ErrorKind::InvalidData`] error on the read
+/// call that observed EOF.
+pub struct VPKVerifyingReader<'a> {
+    inner: VPKEntryReader<'a>,
+    hasher: Hasher,
+    expected_crc32: u32,
+    verified: bool,
 }
 
+impl<'a> VPKVerifyingReader<'a> {
+    fn new(inner: VPKEntryReader<'a>, expected_crc32: u32) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(),
+            expected_crc32,
+            verified: false,
+        }
+    }
+}
+
+impl AsyncRead for VPKVerifyingReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let bytes_read = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        if bytes_read == 0 {
+            if !this.verified {
+                this.verified = true;
+                let actual_crc32 = this.hasher.clone().finalize();
+                if actual_crc32 != this.expected_crc32 {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "CRC32 mismatch: expected {:#010x}, got {:#010x}",
+                            this.expected_crc32, actual_crc32
+                        ),
+                    )));
+                }
+            }
+        } else {
+            this.hasher.update(&buf[..bytes_read]);
+        }
+
+        Poll::Ready(Ok(bytes_read))
+    }
+}
+
+/// Default capacity of the buffer [`VPKEntryReader`] inserts around the
+/// archive-file portion of an entry, chosen to batch away small, repeated
+/// reads when extracting many small entries.
+pub const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
+
 /// A reader over the [`VPKEntry`].
 pub enum VPKEntryReader<'a> {
     /// Only preloaded data must be read.
@@ -64,11 +289,35 @@ pub enum VPKEntryReader<'a> {
         preloaded_bytes_read: usize,
         /// Preloaded data.
         preloaded_data: std::io::Cursor<&'a [u8]>,
-        /// The file that must be read.
-        file: Take<File>,
+        /// The file that must be read, wrapped in a buffer so small reads
+        /// don't each turn into their own syscall.
+        file: BufReader<Take<File>>,
     },
     /// Only the file must be read.
-    FileOnly { file: Take<File> },
+    FileOnly {
+        /// The file that must be read, wrapped in a buffer so small reads
+        /// don't each turn into their own syscall.
+        file: BufReader<Take<File>>,
+    },
+    /// Read from preloaded data first and then a decompressing reader over
+    /// the (LZMA-compressed on disk) archive portion of the entry.
+    #[cfg(feature = "lzma")]
+    PreloadAndDecompressed {
+        /// Length of the preloaded data.
+        preloaded_data_len: usize,
+        /// Number of bytes of the preloaded data read so far.
+        preloaded_bytes_read: usize,
+        /// Preloaded data.
+        preloaded_data: std::io::Cursor<&'a [u8]>,
+        /// The decompressing reader over the archive portion.
+        reader: VPKDecompressingReader,
+    },
+    /// Only the decompressing reader over the archive portion must be read.
+    #[cfg(feature = "lzma")]
+    DecompressedOnly {
+        /// The decompressing reader over the archive portion.
+        reader: VPKDecompressingReader,
+    },
 }
 
 impl AsyncRead for VPKEntryReader<'_> {
@@ -109,15 +358,53 @@ impl AsyncRead for VPKEntryReader<'_> {
                 let bytes_read = ready!(file.read(buf).poll(cx));
                 Poll::Ready(bytes_read)
             }
+            #[cfg(feature = "lzma")]
+            VPKEntryReader::PreloadAndDecompressed {
+                preloaded_data_len,
+                preloaded_bytes_read,
+                preloaded_data,
+                reader,
+            } => {
+                if preloaded_bytes_read >= preloaded_data_len {
+                    Pin::new(reader).poll_read(cx, buf)
+                } else {
+                    let bytes_read = preloaded_data.read(buf)?;
+
+                    let bytes_read = if bytes_read < buf.len() {
+                        let reader_bytes_read =
+                            ready!(Pin::new(&mut *reader).poll_read(cx, &mut buf[bytes_read..]))?;
+                        bytes_read + reader_bytes_read
+                    } else {
+                        bytes_read
+                    };
+
+                    *preloaded_bytes_read += bytes_read;
+
+                    Poll::Ready(Ok(bytes_read))
+                }
+            }
+            #[cfg(feature = "lzma")]
+            VPKEntryReader::DecompressedOnly { reader } => Pin::new(reader).poll_read(cx, buf),
         }
     }
 }
 
 impl<'a> VPKEntryReader<'a> {
-    /// Create a new [`VPKEntryReader`].
+    /// Create a new [`VPKEntryReader`], buffering archive-file reads with
+    /// [`DEFAULT_BUFFER_CAPACITY`].
     pub fn new(preloaded_data: &'a [u8], file: Option<Take<File>>) -> Self {
+        Self::with_capacity(preloaded_data, file, DEFAULT_BUFFER_CAPACITY)
+    }
+
+    /// Create a new [`VPKEntryReader`], buffering archive-file reads
+    /// `capacity` bytes at a time instead of [`DEFAULT_BUFFER_CAPACITY`].
+    ///
+    /// Has no effect when `file` is `None`: the preloaded data is already
+    /// in memory, and buffering it would only add a copy.
+    pub fn with_capacity(preloaded_data: &'a [u8], file: Option<Take<File>>, capacity: usize) -> Self {
         match file {
             Some(file) => {
+                let file = BufReader::with_capacity(capacity, file);
                 if preloaded_data.is_empty() {
                     Self::FileOnly { file }
                 } else {
@@ -134,12 +421,28 @@ impl<'a> VPKEntryReader<'a> {
             },
         }
     }
+
+    /// Create a new [`VPKEntryReader`] backed by a decompressing reader
+    /// over the entry's (compressed) archive portion.
+    #[cfg(feature = "lzma")]
+    pub(crate) fn new_compressed(preloaded_data: &'a [u8], reader: VPKDecompressingReader) -> Self {
+        if preloaded_data.is_empty() {
+            Self::DecompressedOnly { reader }
+        } else {
+            Self::PreloadAndDecompressed {
+                preloaded_data_len: preloaded_data.len(),
+                preloaded_bytes_read: 0,
+                preloaded_data: std::io::Cursor::new(preloaded_data),
+                reader,
+            }
+        }
+    }
 }
 
 /// [`VPKEntry`] header.
 ///
 /// Information about the entry stored in the root VPK.
-#[derive(Debug, BinRead)]
+#[derive(Debug, BinRead, BinWrite)]
 pub struct VPKDirectoryEntry {
     /// 32 bit CRC.
     pub crc32: u32,
@@ -160,4 +463,69 @@ pub struct VPKDirectoryEntry {
     /// Suffix of the header. This seems to be used for ensuring the
     /// entry is read correctly from the root VPK.
     pub suffix: u16,
+    /// Uncompressed size of the archive portion of this entry, when known.
+    ///
+    /// Source 2 VPKs store entries LZMA-compressed, with
+    /// [`Self::file_length`] holding the on-disk compressed size rather
+    /// than the real size. This isn't part of the on-disk directory entry
+    /// layout this crate parses, so it is always `None` here. Unlike
+    /// detecting a compressed entry at all (see [`VPKEntry::is_compressed`],
+    /// which peeks the archive bytes directly), this has no on-disk marker
+    /// to read it from; it exists as the extension point a caller that
+    /// already knows the real size can set so [`VPKEntry::sizes`] reports
+    /// it. `None` means the archive bytes' real size is assumed equal to
+    /// [`Self::file_length`]. With the `lzma` feature, [`VPKEntry::sizes`]
+    /// ignores this field for an entry whose archive bytes start with
+    /// Valve's LZMA header and reports the size stored in that header.
+    #[br(calc = None)]
+    #[bw(ignore)]
+    pub uncompressed_length: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::block_on;
+
+    fn preloaded_entry(data: &[u8], crc32: u32) -> VPKEntry {
+        VPKEntry {
+            dir_entry: VPKDirectoryEntry {
+                crc32,
+                preload_length: data.len() as u16,
+                archive_index: 0,
+                archive_offset: 0,
+                file_length: 0,
+                suffix: 0xffff,
+                uncompressed_length: None,
+            },
+            archive_path: None,
+            preload_data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn verified_reader_accepts_matching_crc32() {
+        let data = b"hello vpk";
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        let crc32 = hasher.finalize();
+
+        let entry = preloaded_entry(data, crc32);
+        let mut reader = block_on(entry.verified_reader()).expect("reader");
+
+        let mut buf = Vec::new();
+        block_on(reader.read_to_end(&mut buf)).expect("crc32 matches, so read should succeed");
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn verified_reader_rejects_mismatching_crc32() {
+        let data = b"hello vpk";
+        let entry = preloaded_entry(data, 0xdead_beef);
+        let mut reader = block_on(entry.verified_reader()).expect("reader");
+
+        let mut buf = Vec::new();
+        let err = block_on(reader.read_to_end(&mut buf)).expect_err("crc32 mismatch should error");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,219 @@
+//! Positional (`pread`-style) reads for concurrent, shared-handle extraction.
+//!
+//! [`VPKEntry::reader`](crate::entry::VPKEntry::reader) opens a fresh
+//! [`async_fs::File`] and seeks it for every entry, so extracting many
+//! entries serializes on open/seek churn and cannot share a handle. The
+//! types here let a single opened handle per physical archive file service
+//! many overlapping reads instead, by reading at an explicit offset rather
+//! than through a mutable seek cursor.
+
+use futures_lite::{ready, AsyncRead};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Read up to `buf.len()` bytes from `file` at `offset`, without a mutable
+/// seek cursor, so the same handle can service other positioned reads
+/// concurrently.
+///
+/// On Unix this is a single [`std::os::unix::fs::FileExt::read_at`] call,
+/// which, like any read, may return fewer bytes than `buf.len()`. On
+/// Windows, [`std::os::windows::fs::FileExt::seek_read`] is looped until
+/// `buf` is full or EOF is reached.
+#[cfg(unix)]
+fn positioned_read(file: &std::fs::File, buf: &mut [u8], offset: u64) -> IoResult<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn positioned_read(file: &std::fs::File, buf: &mut [u8], offset: u64) -> IoResult<usize> {
+    use std::os::windows::fs::FileExt;
+
+    let mut total = 0;
+    while total < buf.len() {
+        match file.seek_read(&mut buf[total..], offset + total as u64) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// A pending positioned read: the buffer handed to [`ReadAt::read_at`],
+/// filled in place, and how many of its bytes are valid.
+type ReadAtFuture = Pin<Box<dyn Future<Output = IoResult<(Vec<u8>, usize)>> + Send>>;
+
+/// A source that can be read from at an arbitrary offset without a
+/// mutable seek cursor, so one handle may service many concurrent,
+/// overlapping reads.
+pub trait ReadAt {
+    /// Read up to `buf.len()` bytes starting at `offset`, filling `buf` in
+    /// place and handing it back along with how many of its bytes are
+    /// valid. Reading past EOF yields `0`. `buf` is moved into the read and
+    /// handed back when it completes, so the caller can reuse it for a
+    /// later positioned read instead of allocating fresh each time.
+    fn read_at(&self, buf: Vec<u8>, offset: u64) -> ReadAtFuture;
+}
+
+/// A shared, positionable handle onto one physical archive file.
+///
+/// Wraps the [`std::fs::File`] in an [`Arc`] so [`positioned_read`] can be
+/// called from many concurrent positioned reads off one file descriptor —
+/// without `dup`-ing it per read, as cloning a [`std::fs::File`] would —
+/// and dispatches the blocking syscall onto the `blocking` thread pool used
+/// throughout `async_fs`.
+pub struct SharedArchive {
+    file: Arc<std::fs::File>,
+}
+
+impl SharedArchive {
+    fn open(path: &Path) -> IoResult<Self> {
+        Ok(Self {
+            file: Arc::new(std::fs::File::open(path)?),
+        })
+    }
+}
+
+impl ReadAt for SharedArchive {
+    fn read_at(&self, mut buf: Vec<u8>, offset: u64) -> ReadAtFuture {
+        let file = Arc::clone(&self.file);
+        Box::pin(async move {
+            blocking::unblock(move || {
+                let n = positioned_read(&file, &mut buf, offset)?;
+                Ok::<_, Error>((buf, n))
+            })
+            .await
+        })
+    }
+}
+
+/// A cache of [`SharedArchive`] handles keyed by archive path, so that
+/// every [`crate::entry::VPKEntry`] pointing at the same physical file
+/// reuses one open handle instead of opening its own.
+#[derive(Clone, Default)]
+pub struct ArchiveHandles {
+    inner: Arc<Mutex<HashMap<PathBuf, Arc<SharedArchive>>>>,
+}
+
+impl ArchiveHandles {
+    /// Create an empty handle cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the shared handle for `path`, opening and caching it if this
+    /// is the first request for that archive.
+    pub fn open(&self, path: &Path) -> IoResult<Arc<SharedArchive>> {
+        let mut handles = self.inner.lock().expect("ArchiveHandles mutex poisoned");
+
+        if let Some(handle) = handles.get(path) {
+            return Ok(Arc::clone(handle));
+        }
+
+        let handle = Arc::new(SharedArchive::open(path)?);
+        handles.insert(path.to_path_buf(), Arc::clone(&handle));
+        Ok(handle)
+    }
+}
+
+/// A reader over a [`crate::entry::VPKEntry`] that reads the archive
+/// portion via positioned reads against a shared [`SharedArchive`] handle,
+/// rather than a per-entry seek cursor. Preload bytes are served directly
+/// from memory first, as with [`crate::entry::VPKEntryReader`].
+pub struct VPKPositionedReader {
+    preload_data: Vec<u8>,
+    preload_pos: usize,
+    archive: Option<Arc<SharedArchive>>,
+    archive_offset: u64,
+    archive_remaining: u64,
+    /// Buffer handed to [`ReadAt::read_at`] and reclaimed once it resolves,
+    /// so consecutive positioned reads reuse one allocation instead of
+    /// each allocating their own scratch `Vec`.
+    scratch: Vec<u8>,
+    pending: Option<ReadAtFuture>,
+}
+
+impl VPKPositionedReader {
+    /// Create a reader that only ever serves preloaded bytes.
+    pub(crate) fn preloaded(preload_data: Vec<u8>) -> Self {
+        Self {
+            preload_data,
+            preload_pos: 0,
+            archive: None,
+            archive_offset: 0,
+            archive_remaining: 0,
+            scratch: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Create a reader that serves preloaded bytes first, then `len`
+    /// bytes of `archive` starting at `offset`.
+    pub(crate) fn new(preload_data: Vec<u8>, archive: Arc<SharedArchive>, offset: u64, len: u64) -> Self {
+        Self {
+            preload_data,
+            preload_pos: 0,
+            archive: Some(archive),
+            archive_offset: offset,
+            archive_remaining: len,
+            scratch: Vec::new(),
+            pending: None,
+        }
+    }
+}
+
+impl AsyncRead for VPKPositionedReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+
+        if this.preload_pos < this.preload_data.len() {
+            let remaining = &this.preload_data[this.preload_pos..];
+            let n = remaining.len().min(buf.len());
+            buf[..n].copy_from_slice(&remaining[..n]);
+            this.preload_pos += n;
+            return Poll::Ready(Ok(n));
+        }
+
+        if this.archive_remaining == 0 || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            if let Some(pending) = this.pending.as_mut() {
+                let (owned, n) = ready!(pending.as_mut().poll(cx))?;
+                this.pending = None;
+
+                if n > 0 {
+                    buf[..n].copy_from_slice(&owned[..n]);
+                    this.archive_offset += n as u64;
+                    this.archive_remaining -= n as u64;
+                }
+                this.scratch = owned;
+
+                return Poll::Ready(Ok(n));
+            }
+
+            let archive = this
+                .archive
+                .as_ref()
+                .expect("archive_remaining > 0 implies an archive handle")
+                .clone();
+            let to_read = buf.len().min(this.archive_remaining as usize);
+            let offset = this.archive_offset;
+
+            let mut scratch = std::mem::take(&mut this.scratch);
+            scratch.resize(to_read, 0);
+
+            this.pending = Some(archive.read_at(scratch, offset));
+        }
+    }
+}
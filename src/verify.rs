@@ -0,0 +1,285 @@
+//! Validation of VPK v2's trailing MD5 checksum and signature sections.
+//!
+//! Version 2 VPKs append three sections after the header, directory tree
+//! and archive data: a list of per-archive-chunk MD5 hashes, a fixed
+//! "other MD5" section tying those to a whole-file checksum, and an
+//! optional RSA signature. Nothing else in this crate parses or checks
+//! them; [`VPKChecksums::verify`] lets a caller detect a tampered or
+//! truncated `_dir.vpk` before trusting any [`crate::entry::VPKEntry`] in
+//! it, complementing the per-entry [`crate::entry::VPKEntry::verified_reader`]
+//! CRC32 check.
+
+use crate::writer::{archive_path, archive_stem};
+use binrw::BinRead;
+use md5::{Digest, Md5};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// VPK directory header signature.
+const VPK_SIGNATURE: u32 = 0x55aa_1234;
+
+/// The fixed 12-byte VPK v1 header, read directly from the start of a
+/// `_dir.vpk` file.
+#[derive(Debug, BinRead)]
+struct HeaderV1 {
+    signature: u32,
+    version: u32,
+    tree_size: u32,
+}
+
+/// The VPK v2 header fields, immediately following [`HeaderV1`].
+#[derive(Debug, BinRead)]
+struct HeaderV2 {
+    file_data_section_size: u32,
+    archive_md5_section_size: u32,
+    other_md5_section_size: u32,
+    signature_section_size: u32,
+}
+
+/// One entry of the archive MD5 section: the MD5 of a byte range within a
+/// split archive file.
+#[derive(Debug, BinRead, Clone)]
+pub struct ArchiveMd5Entry {
+    /// Index of the archive (`_NNN.vpk`) the range is in.
+    pub archive_index: u32,
+    /// Offset within the archive the hashed range starts at.
+    pub starting_offset: u32,
+    /// Number of bytes hashed.
+    pub count: u32,
+    /// MD5 of `archive[starting_offset..starting_offset + count]`.
+    pub md5: [u8; 16],
+}
+
+/// The fixed-size section tying the archive MD5 entries to a whole-file
+/// checksum.
+#[derive(Debug, BinRead)]
+pub struct OtherMd5Section {
+    /// MD5 of the header and directory tree bytes.
+    pub tree_checksum: [u8; 16],
+    /// MD5 of the archive MD5 section itself.
+    pub archive_md5_section_checksum: [u8; 16],
+    /// MD5 of the file from its start through [`Self::archive_md5_section_checksum`]
+    /// (i.e. the header, tree, data and archive MD5 section, plus
+    /// [`Self::tree_checksum`] and [`Self::archive_md5_section_checksum`]
+    /// themselves — but not this field or the signature section).
+    pub whole_file_checksum: [u8; 16],
+}
+
+/// Byte length of [`OtherMd5Section::tree_checksum`] and
+/// [`OtherMd5Section::archive_md5_section_checksum`], the two fields that
+/// precede (and so fall within the coverage of)
+/// [`OtherMd5Section::whole_file_checksum`].
+const OTHER_MD5_LEADING_FIELDS_LEN: usize = 16 + 16;
+
+/// The trailing MD5 and signature sections of a VPK v2 directory file.
+#[derive(Debug)]
+pub struct VPKChecksums {
+    /// Per-archive-chunk MD5 hashes.
+    pub archive_md5_entries: Vec<ArchiveMd5Entry>,
+    /// The fixed "other MD5" section.
+    pub other: OtherMd5Section,
+    /// DER-encoded RSA public key, empty when the archive isn't signed.
+    pub public_key: Vec<u8>,
+    /// RSA signature over the file, empty when the archive isn't signed.
+    pub signature: Vec<u8>,
+}
+
+/// Which trailing section failed to validate.
+#[derive(Debug)]
+pub enum ChecksumError {
+    /// Reading or parsing the directory file failed.
+    Io(Error),
+    /// The file isn't a VPK v2 archive, so it has no trailing sections.
+    NotV2,
+    /// The directory tree checksum did not match.
+    TreeMismatch,
+    /// The archive MD5 section's own checksum did not match.
+    ArchiveMd5SectionMismatch,
+    /// The whole-file checksum did not match.
+    WholeFileMismatch,
+    /// A per-chunk archive MD5 did not match that chunk's actual bytes.
+    ArchiveChunkMismatch {
+        /// Index of the archive file the mismatching chunk is in.
+        archive_index: u32,
+    },
+    /// The RSA signature did not verify against the supplied public key.
+    SignatureMismatch,
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read VPK checksum sections: {err}"),
+            Self::NotV2 => write!(f, "not a VPK v2 archive; no checksum sections to validate"),
+            Self::TreeMismatch => write!(f, "directory tree checksum mismatch"),
+            Self::ArchiveMd5SectionMismatch => write!(f, "archive MD5 section checksum mismatch"),
+            Self::WholeFileMismatch => write!(f, "whole-file checksum mismatch"),
+            Self::ArchiveChunkMismatch { archive_index } => {
+                write!(f, "archive MD5 mismatch in archive index {archive_index}")
+            }
+            Self::SignatureMismatch => write!(f, "signature verification failed"),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+impl From<Error> for ChecksumError {
+    fn from(err: Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<binrw::Error> for ChecksumError {
+    fn from(err: binrw::Error) -> Self {
+        Self::Io(Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+}
+
+impl VPKChecksums {
+    /// Parse and validate the trailing sections of the VPK v2 directory
+    /// file at `dir_path`, also verifying each archive chunk's MD5 against
+    /// the corresponding `_NNN.vpk` file on disk.
+    pub fn verify(dir_path: &Path) -> Result<Self, ChecksumError> {
+        let data = fs::read(dir_path)?;
+        let mut cursor = std::io::Cursor::new(&data[..]);
+
+        let header = HeaderV1::read_le(&mut cursor)?;
+        if header.signature != VPK_SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "bad VPK signature").into());
+        }
+        if header.version < 2 {
+            return Err(ChecksumError::NotV2);
+        }
+        let header_v2 = HeaderV2::read_le(&mut cursor)?;
+
+        let header_len = cursor.position() as usize;
+        let tree_end = header_len + header.tree_size as usize;
+        let data_end = tree_end + header_v2.file_data_section_size as usize;
+        let archive_md5_end = data_end + header_v2.archive_md5_section_size as usize;
+        let other_md5_end = archive_md5_end + header_v2.other_md5_section_size as usize;
+        let signature_end = other_md5_end + header_v2.signature_section_size as usize;
+
+        let data = data
+            .get(..signature_end)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "directory file truncated"))?;
+
+        let mut archive_md5_cursor = std::io::Cursor::new(&data[data_end..archive_md5_end]);
+        let mut archive_md5_entries = Vec::new();
+        while (archive_md5_cursor.position() as usize) < archive_md5_cursor.get_ref().len() {
+            archive_md5_entries.push(ArchiveMd5Entry::read_le(&mut archive_md5_cursor)?);
+        }
+
+        let mut other_cursor = std::io::Cursor::new(&data[archive_md5_end..other_md5_end]);
+        let other = OtherMd5Section::read_le(&mut other_cursor)?;
+
+        let mut signature_cursor = std::io::Cursor::new(&data[other_md5_end..signature_end]);
+        let (public_key, signature) = if header_v2.signature_section_size > 0 {
+            let public_key_size = u32::read_le(&mut signature_cursor)?;
+            let mut public_key = vec![0u8; public_key_size as usize];
+            std::io::Read::read_exact(&mut signature_cursor, &mut public_key)?;
+
+            let signature_size = u32::read_le(&mut signature_cursor)?;
+            let mut signature = vec![0u8; signature_size as usize];
+            std::io::Read::read_exact(&mut signature_cursor, &mut signature)?;
+
+            (public_key, signature)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        if md5_of(&data[..tree_end]) != other.tree_checksum {
+            return Err(ChecksumError::TreeMismatch);
+        }
+        if md5_of(&data[data_end..archive_md5_end]) != other.archive_md5_section_checksum {
+            return Err(ChecksumError::ArchiveMd5SectionMismatch);
+        }
+        let whole_file_end = archive_md5_end + OTHER_MD5_LEADING_FIELDS_LEN;
+        if md5_of(&data[..whole_file_end]) != other.whole_file_checksum {
+            return Err(ChecksumError::WholeFileMismatch);
+        }
+
+        verify_archive_chunks(dir_path, &data[tree_end..data_end], &archive_md5_entries)?;
+
+        Ok(Self {
+            archive_md5_entries,
+            other,
+            public_key,
+            signature,
+        })
+    }
+
+    /// Verify [`Self::signature`] over `signed_region` (conventionally the
+    /// whole directory file up to the start of the signature section)
+    /// against a supplied DER-encoded RSA public key.
+    #[cfg(feature = "signature")]
+    pub fn verify_signature(
+        &self,
+        signed_region: &[u8],
+        public_key: &[u8],
+    ) -> Result<(), ChecksumError> {
+        use rsa::pkcs1v15::Pkcs1v15Sign;
+        use rsa::pkcs8::DecodePublicKey;
+        use rsa::RsaPublicKey;
+        use sha1::{Digest, Sha1};
+
+        let key = RsaPublicKey::from_public_key_der(public_key)
+            .map_err(|_| ChecksumError::SignatureMismatch)?;
+        let hashed = Sha1::digest(signed_region);
+
+        key.verify(Pkcs1v15Sign::new::<Sha1>(), &hashed, &self.signature)
+            .map_err(|_| ChecksumError::SignatureMismatch)
+    }
+}
+
+fn md5_of(bytes: &[u8]) -> [u8; 16] {
+    Md5::digest(bytes).into()
+}
+
+/// `archive_index` value meaning an archive MD5 entry's bytes are not in a
+/// separate `_NNN.vpk` file but embedded directly in the `_dir.vpk`'s own
+/// file-data section.
+const DIR_EMBEDDED_ARCHIVE_INDEX: u32 = 0x7fff;
+
+/// Recompute each archive MD5 entry's hash against the bytes actually
+/// present at that range, whether that's a `_NNN.vpk` file or (for
+/// [`DIR_EMBEDDED_ARCHIVE_INDEX`]) `embedded_data`, the directory file's own
+/// file-data section.
+fn verify_archive_chunks(
+    dir_path: &Path,
+    embedded_data: &[u8],
+    entries: &[ArchiveMd5Entry],
+) -> Result<(), ChecksumError> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let stem = archive_stem(dir_path)?;
+
+    for entry in entries {
+        let chunk = if entry.archive_index == DIR_EMBEDDED_ARCHIVE_INDEX {
+            let start = entry.starting_offset as usize;
+            let end = start + entry.count as usize;
+            embedded_data
+                .get(start..end)
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "embedded archive chunk out of range"))?
+                .to_vec()
+        } else {
+            let path = archive_path(&stem, entry.archive_index as u16);
+            let mut file = fs::File::open(&path)?;
+            file.seek(SeekFrom::Start(entry.starting_offset as u64))?;
+
+            let mut chunk = vec![0u8; entry.count as usize];
+            file.read_exact(&mut chunk)?;
+            chunk
+        };
+
+        if md5_of(&chunk) != entry.md5 {
+            return Err(ChecksumError::ArchiveChunkMismatch {
+                archive_index: entry.archive_index,
+            });
+        }
+    }
+
+    Ok(())
+}